@@ -3,24 +3,36 @@
 //! Provides ways to construct a filtering predicate from cli args, crawl a
 //! directory conditional on that predicate, and format error messges.
 
+mod exec;
+mod ignore;
+mod watch;
+
 use clap::ArgMatches;
+pub use exec::ExecBatch;
+use exec::ExecSpec;
 use fnmatch_sys::{fnmatch, FNM_NOMATCH};
-use std::collections::HashSet;
+pub use ignore::{IgnoreCache, IgnoreStack};
+use regex::Regex;
+use std::collections::{HashSet, VecDeque};
 use std::ffi::CString;
 use std::fs::Metadata;
 use std::io;
 use std::os::unix::fs::MetadataExt;
 use std::os::unix::{ffi::OsStrExt, fs::FileTypeExt};
-use std::path::Path;
-use subprocess::Exec;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+pub use watch::watch;
 
 /// Describes a command line given predicate.
 ///
 /// A heap allocated closure that takes a path (describing a file) and it's
 /// associated metadata and returns either an io error or a bool. This indicates
 /// wheither to continue executing predicates or return false. Predicates are
-/// written to short circut, so their order of application matters.
-type Predicate = Box<dyn Fn(&Path, &Metadata) -> io::Result<bool>>;
+/// written to short circut, so their order of application matters. `Send +
+/// Sync` is required so a predicate can be shared across the worker threads
+/// of [`crawl_path_parallel`].
+type Predicate = Box<dyn Fn(&Path, &Metadata) -> io::Result<bool> + Send + Sync>;
 
 /// Provides a filter for the --type flag.
 ///
@@ -75,6 +87,60 @@ fn time_predicate(predicate: Predicate, accepted: i32) -> Predicate {
     })
 }
 
+/// A parsed `--size` argument: `[+-]?<num><suffix>`, where the optional
+/// leading `+`/`-` means greater-than/less-than (exact match otherwise) and
+/// the suffix is a multiplier (`b` = 512-byte blocks, the default if no
+/// suffix is given; `c` = bytes; `k`/`M`/`G` = powers of 1024), matching
+/// `find`/`fd` conventions.
+pub struct SizeFilter {
+    cmp: std::cmp::Ordering,
+    bytes: u64,
+}
+
+impl SizeFilter {
+    /// Parse a `--size` argument. Used both by `getopts`'s `validator` (to
+    /// reject a bad argument up front) and by [`form_predicate`] (which can
+    /// then assume parsing succeeds).
+    pub fn parse(s: &str) -> Result<SizeFilter, String> {
+        let (cmp, rest) = match s.strip_prefix('+') {
+            Some(rest) => (std::cmp::Ordering::Greater, rest),
+            None => match s.strip_prefix('-') {
+                Some(rest) => (std::cmp::Ordering::Less, rest),
+                None => (std::cmp::Ordering::Equal, s),
+            },
+        };
+        let split = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let (num, suffix) = rest.split_at(split);
+        if num.is_empty() {
+            return Err(format!("invalid size `{}': expected a number", s));
+        }
+        let num: u64 = num
+            .parse()
+            .map_err(|e: std::num::ParseIntError| e.to_string())?;
+        let multiplier: u64 = match suffix {
+            "" | "b" => 512,
+            "c" => 1,
+            "k" => 1024,
+            "M" => 1024 * 1024,
+            "G" => 1024 * 1024 * 1024,
+            other => return Err(format!("invalid size suffix `{}'", other)),
+        };
+        let bytes = num
+            .checked_mul(multiplier)
+            .ok_or_else(|| format!("size `{}' too large", s))?;
+        Ok(SizeFilter { cmp, bytes })
+    }
+}
+
+/// Filters on the `--size` argument.
+fn size_predicate(predicate: Predicate, filter: SizeFilter) -> Predicate {
+    Box::new(
+        move |p, m: &Metadata| Ok(predicate(p, m)? && m.len().cmp(&filter.bytes) == filter.cmp),
+    )
+}
+
 /// Filters on the `--name` argument.
 ///
 /// Panics when `fnmatch` provides an error code.
@@ -102,15 +168,64 @@ fn name_predicate(predicate: Predicate, name: CString) -> Predicate {
     })
 }
 
-/// Filters on the `--exec` predicate.
+/// Filters on the `--regex`/`-r` argument.
+///
+/// Matches `regex` against the file's last path component, or the full path
+/// when `full_path` is set. Patterns are validated (compiled) up front in
+/// `getopts`, so a `Regex` can be assumed to already exist here.
+fn regex_predicate(predicate: Predicate, regex: Regex, full_path: bool) -> Predicate {
+    Box::new(move |p, m| {
+        Ok(predicate(p, m)? && {
+            if full_path {
+                regex.is_match(&p.to_string_lossy())
+            } else {
+                let name = p.components().last().unwrap().as_os_str();
+                regex.is_match(&name.to_string_lossy())
+            }
+        })
+    })
+}
+
+/// Filters on the `--exec command ... ;` predicate.
 ///
 /// print_anyway corrosponds to the -print command. It corrosponds to running
-/// the command, but ignoring the result.
-fn exec_predicate(predicate: Predicate, command: String, print_anyway: bool) -> Predicate {
+/// the command, but ignoring the result. The command is spawned directly
+/// (`argv[0]` plus arguments) with no shell in between, so filenames
+/// containing shell metacharacters can't be misinterpreted; `{}` is replaced
+/// with the current path in every argument it occurs in.
+fn exec_predicate(predicate: Predicate, argv: Vec<String>, print_anyway: bool) -> Predicate {
     Box::new(move |p, m| {
         Ok(predicate(p, m)? && {
-            match Exec::shell(command.to_string().replace("{}", &p.to_string_lossy())).join() {
-                Ok(t) => t.success() && print_anyway,
+            let path = p.to_string_lossy();
+            let args = argv.iter().map(|a| a.replace("{}", &path));
+            match exec::run(args) {
+                Ok(success) => success && print_anyway,
+                Err(e) => {
+                    Error::Custom(&e).sig();
+                    false
+                }
+            }
+        })
+    })
+}
+
+/// Filters on the `--exec command ... +` predicate.
+///
+/// Unlike [`exec_predicate`], success is not known per-file: the command
+/// only actually runs once a batch of matched paths is flushed (see
+/// [`ExecBatch`]), so a match just queues the path. The predicate then
+/// returns `print_anyway`, as [`exec_predicate`] does for a successful run:
+/// without `-print` the path is not printed and the predicates wrapped
+/// around this one are not run.
+fn exec_batch_predicate(
+    predicate: Predicate,
+    batch: Arc<ExecBatch>,
+    print_anyway: bool,
+) -> Predicate {
+    Box::new(move |p, m| {
+        Ok(predicate(p, m)? && {
+            match batch.push(p.to_path_buf()) {
+                Ok(()) => print_anyway,
                 Err(e) => {
                     Error::Custom(&e).sig();
                     false
@@ -125,31 +240,57 @@ type SigError = bool;
 
 /// Recursivly traverse `path`. Only adds if `predicate(path, metadata(path))`
 /// returns true. If a file is a directory, `crawl_path` will still traverse.
-/// Symlinks will be followed if `follow_syms` is `true`.
+/// Symlinks will be followed if `follow_syms` is `true`. Paths matched by
+/// `ignore` (built from `.gitignore`/`.ignore`/`.fdignore` files encountered
+/// along the way) are skipped entirely, including their subtrees.
+///
+/// `depth` is the starting point's distance from `path` (the starting point
+/// itself is called with `depth` `0`). Matches shallower than `mindepth` are
+/// not printed, and directories at `maxdepth` are not descended into at all,
+/// rather than merely having their contents filtered out afterwards.
+#[allow(clippy::too_many_arguments)]
 pub fn crawl_path(
     path: &Path,
     predicate: &Predicate,
     follow_syms: bool,
     visited: &mut HashSet<u64>,
+    ignore: &mut IgnoreStack,
+    depth: usize,
+    mindepth: usize,
+    maxdepth: Option<usize>,
 ) -> Result<SigError, io::Error> {
     let meta = if follow_syms {
         std::fs::metadata(path)?
     } else {
         std::fs::symlink_metadata(path)?
     };
-    if predicate(path, &meta)? {
+    if ignore.is_ignored(path, meta.is_dir()) {
+        return Ok(false);
+    }
+    if depth >= mindepth && predicate(path, &meta)? {
         println!("{}", path.display());
     }
     let mut sig_error = false;
     if meta.is_dir()
         && (follow_syms || !meta.file_type().is_symlink())
+        && maxdepth.map_or(true, |max| depth < max)
         && visited.insert(meta.ino())
     // This tests if meta.ino() is already in
     // visited
     {
+        ignore.enter(path);
         for fs in std::fs::read_dir(path)? {
             let fs = fs?.path();
-            match crawl_path(&fs, predicate, follow_syms, visited) {
+            match crawl_path(
+                &fs,
+                predicate,
+                follow_syms,
+                visited,
+                ignore,
+                depth + 1,
+                mindepth,
+                maxdepth,
+            ) {
                 Err(e) => {
                     match e.kind() {
                         io::ErrorKind::NotFound => println!("{}", fs.display()),
@@ -160,14 +301,235 @@ pub fn crawl_path(
                 Ok(sig) => sig_error = sig,
             }
         }
+        ignore.leave();
     }
     Ok(sig_error)
 }
 
+/// Work queued for the worker pool in [`crawl_path_parallel`]: a path (the
+/// starting point, or any entry a `read_dir` turned up, file or directory)
+/// still waiting to be visited, along with its distance from the starting
+/// point.
+struct WorkQueue {
+    paths: Mutex<VecDeque<(PathBuf, usize)>>,
+    /// Notified whenever a path is pushed, or when the pool has finished, so
+    /// idle workers can wake up and either steal work or exit.
+    ready: Condvar,
+    /// Number of workers currently holding a path (as opposed to waiting on
+    /// the queue). The pool is done once this is `0` and the queue is empty.
+    busy: AtomicUsize,
+}
+
+impl WorkQueue {
+    fn push(&self, path: PathBuf, depth: usize) {
+        self.paths.lock().unwrap().push_back((path, depth));
+        self.ready.notify_all();
+    }
+
+    /// Pop the next path to visit, blocking until one is available or every
+    /// worker is idle with nothing left to do (in which case `None` is
+    /// returned and all waiters are woken so they can shut down too).
+    fn pop(&self) -> Option<(PathBuf, usize)> {
+        let mut paths = self.paths.lock().unwrap();
+        loop {
+            if let Some(entry) = paths.pop_front() {
+                self.busy.fetch_add(1, Ordering::SeqCst);
+                return Some(entry);
+            }
+            if self.busy.load(Ordering::SeqCst) == 0 {
+                self.ready.notify_all();
+                return None;
+            }
+            paths = self.ready.wait(paths).unwrap();
+        }
+    }
+
+    /// Mark the path most recently [`pop`](WorkQueue::pop)ped as finished.
+    /// The `busy` decrement and the wake-up happen while holding the same
+    /// lock `pop` checks its termination condition under, so a worker that
+    /// observes the queue empty can never miss the notification for the
+    /// last other worker going idle.
+    fn done(&self) {
+        let _paths = self.paths.lock().unwrap();
+        self.busy.fetch_sub(1, Ordering::SeqCst);
+        self.ready.notify_all();
+    }
+}
+
+/// Recursively traverse `path` across `threads` worker threads instead of a
+/// single call stack. Every entry a `read_dir` turns up (files as well as
+/// subdirectories) is pushed onto a shared [`WorkQueue`] that every thread
+/// pops from (and pushes back onto), so the traversal fans stats and
+/// directory reads across the pool the way `fd` does instead of descending
+/// one directory at a time.
+///
+/// Output ordering across files is not guaranteed (workers print as soon as
+/// they find a match), but writes themselves are serialized so lines never
+/// interleave. The starting point failing to stat behaves like
+/// [`crawl_path`]: the first such `io::Error` is recorded and returned to the
+/// caller once every thread has stopped, rather than the pool aborting early
+/// (workers keep draining the queue in the meantime). Per-entry errors below
+/// the starting point only flip the returned [`SigError`], matching
+/// [`crawl_path`]'s behaviour: an entry that fails to stat with `NotFound`
+/// (a dangling symlink under `-L`, for instance) has its path printed, and
+/// any other error is reported through [`Error::sig`].
+///
+/// `mindepth`/`maxdepth` bound recursion exactly as they do for
+/// [`crawl_path`]: directories at `maxdepth` are never `read_dir`'d, so
+/// nothing below them is queued at all, which shrinks the pool's total work
+/// rather than just filtering what gets printed.
+#[allow(clippy::too_many_arguments)]
+pub fn crawl_path_parallel(
+    path: &Path,
+    predicate: &Predicate,
+    follow_syms: bool,
+    threads: usize,
+    respect_ignore: bool,
+    mindepth: usize,
+    maxdepth: Option<usize>,
+) -> Result<SigError, io::Error> {
+    let threads = threads.max(1);
+    let queue = WorkQueue {
+        paths: Mutex::new(VecDeque::from([(path.to_path_buf(), 0)])),
+        ready: Condvar::new(),
+        busy: AtomicUsize::new(0),
+    };
+    let visited: Mutex<HashSet<u64>> = Mutex::new(HashSet::new());
+    let stdout = Mutex::new(io::stdout());
+    let sig_error = AtomicBool::new(false);
+    let fatal: Mutex<Option<io::Error>> = Mutex::new(None);
+    let ignore = IgnoreCache::new(path, respect_ignore);
+
+    std::thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| {
+                while let Some((path, depth)) = queue.pop() {
+                    visit_parallel(
+                        &path,
+                        predicate,
+                        follow_syms,
+                        &queue,
+                        &visited,
+                        &stdout,
+                        &sig_error,
+                        &fatal,
+                        &ignore,
+                        depth,
+                        mindepth,
+                        maxdepth,
+                    );
+                    queue.done();
+                }
+            });
+        }
+    });
+
+    match fatal.into_inner().unwrap() {
+        Some(e) => Err(e),
+        None => Ok(sig_error.load(Ordering::SeqCst)),
+    }
+}
+
+/// Visit a single queued path: apply `predicate`, print a match, and (for
+/// directories) `read_dir` it and push its children back onto `queue`.
+///
+/// Errors that would abort a single-threaded `crawl_path` are instead
+/// recorded: the first one wins (further workers keep going so the pool can
+/// still shut down cleanly) and is returned to the caller of
+/// [`crawl_path_parallel`] once every thread has stopped.
+#[allow(clippy::too_many_arguments)]
+fn visit_parallel(
+    path: &Path,
+    predicate: &Predicate,
+    follow_syms: bool,
+    queue: &WorkQueue,
+    visited: &Mutex<HashSet<u64>>,
+    stdout: &Mutex<io::Stdout>,
+    sig_error: &AtomicBool,
+    fatal: &Mutex<Option<io::Error>>,
+    ignore: &IgnoreCache,
+    depth: usize,
+    mindepth: usize,
+    maxdepth: Option<usize>,
+) {
+    use std::io::Write;
+
+    let meta = if follow_syms {
+        std::fs::metadata(path)
+    } else {
+        std::fs::symlink_metadata(path)
+    };
+    let meta = match meta {
+        Ok(meta) => meta,
+        Err(e) => {
+            if depth == 0 {
+                // The starting point itself failed to stat: propagate like
+                // `crawl_path` does, so `main` reports it the same way
+                // instead of printing the bare path as a false match.
+                fatal.lock().unwrap().get_or_insert(e);
+            } else {
+                match e.kind() {
+                    io::ErrorKind::NotFound => {
+                        let mut out = stdout.lock().unwrap();
+                        let _ = writeln!(out, "{}", path.display());
+                    }
+                    _ => Error::from_io(e, path.display()).sig(),
+                }
+            }
+            sig_error.store(true, Ordering::SeqCst);
+            return;
+        }
+    };
+
+    if ignore.is_ignored(path, meta.is_dir()) {
+        return;
+    }
+
+    if depth >= mindepth {
+        match predicate(path, &meta) {
+            Ok(true) => {
+                let mut out = stdout.lock().unwrap();
+                let _ = writeln!(out, "{}", path.display());
+            }
+            Ok(false) => {}
+            Err(e) => {
+                fatal.lock().unwrap().get_or_insert(e);
+                sig_error.store(true, Ordering::SeqCst);
+                return;
+            }
+        }
+    }
+
+    if meta.is_dir()
+        && (follow_syms || !meta.file_type().is_symlink())
+        && maxdepth.map_or(true, |max| depth < max)
+        && visited.lock().unwrap().insert(meta.ino())
+    {
+        let entries = match std::fs::read_dir(path) {
+            Ok(entries) => entries,
+            Err(e) => {
+                Error::from_io(e, path.display()).sig();
+                sig_error.store(true, Ordering::SeqCst);
+                return;
+            }
+        };
+        for entry in entries {
+            match entry {
+                Ok(entry) => queue.push(entry.path(), depth + 1),
+                Err(e) => {
+                    Error::from_io(e, path.display()).sig();
+                    sig_error.store(true, Ordering::SeqCst);
+                }
+            }
+        }
+    }
+}
+
 /// Takes args given and forms a predicate to correctly filter them.
-pub fn form_predicate(opts: &ArgMatches) -> Predicate {
+pub fn form_predicate(opts: &ArgMatches) -> (Predicate, Vec<Arc<ExecBatch>>) {
     // Default predicate: everything passes.
     let mut predicate: Predicate = Box::new(|_, _| Ok(true));
+    let mut exec_batches = Vec::new();
     if let Some(types) = opts.values_of("type").take() {
         // Apply type arg
         predicate = type_predicate(predicate, types.map(|f| f.to_string()).collect());
@@ -179,18 +541,38 @@ pub fn form_predicate(opts: &ArgMatches) -> Predicate {
             name_predicate(predicate, name)
         })
     }
+    if let Some(regexes) = opts.values_of("regex").take() {
+        let full_path = opts.is_present("full-path");
+        predicate = regexes.fold(predicate, |predicate, regex| {
+            // Already validated by `getopts`'s `validator`.
+            regex_predicate(predicate, Regex::new(regex).unwrap(), full_path)
+        })
+    }
     if let Some(mtimes) = opts.values_of("mtime").take() {
         predicate = mtimes.fold(predicate, |predicate, mtime| {
             time_predicate(predicate, mtime.parse().unwrap())
         })
     }
+    if let Some(sizes) = opts.values_of("size").take() {
+        predicate = sizes.fold(predicate, |predicate, size| {
+            // Already validated by `getopts`'s `validator`.
+            size_predicate(predicate, SizeFilter::parse(size).unwrap())
+        })
+    }
     if let Some(execs) = opts.values_of("exec").take() {
         let print_anyway = opts.is_present("print");
         predicate = execs.fold(predicate, |predicate, exec| {
-            exec_predicate(predicate, exec.to_string(), print_anyway)
+            let spec = ExecSpec::parse(exec);
+            if spec.batch {
+                let batch = Arc::new(ExecBatch::new(spec.argv));
+                exec_batches.push(batch.clone());
+                exec_batch_predicate(predicate, batch, print_anyway)
+            } else {
+                exec_predicate(predicate, spec.argv, print_anyway)
+            }
         })
     }
-    predicate
+    (predicate, exec_batches)
 }
 
 /// Provides gnu-find compatible error handling.
@@ -271,8 +653,16 @@ where
     for arg in args.into_iter() {
         let arg = arg.into();
         if let Some(mut cmd) = exec {
-            if &arg == ";" {
-                out.push(cmd.join(" "));
+            if &arg == ";" || &arg == "+" {
+                // Encode the `;`/`+` terminator as a leading NUL-joined
+                // field so the exec predicate can tell the two apart
+                // without re-parsing shell syntax, then preserve the
+                // command's argv tokens verbatim (no shell joining) so `{}`
+                // substitution stays a plain argv substitution later on.
+                let marker = if &arg == "+" { "+" } else { ";" };
+                let mut encoded = vec![marker.to_string()];
+                encoded.extend(cmd);
+                out.push(encoded.join("\u{0}"));
                 exec = None;
             } else {
                 exec = Some({
@@ -287,6 +677,7 @@ where
                 "-name" => out.push(String::from("--name")),
                 "-type" => out.push(String::from("--type")),
                 "-mtime" => out.push(String::from("--mtime")),
+                "-size" => out.push(String::from("--size")),
                 "-exec" => {
                     out.push(String::from("--exec"));
                     exec = Some(Vec::new());
@@ -326,7 +717,7 @@ mod test {
                 "--name",
                 "thing*",
                 "--exec",
-                "cmd -type",
+                ";\u{0}cmd\u{0}-type",
                 "--type",
                 "b"
             ]
@@ -335,4 +726,16 @@ mod test {
             .collect::<Vec<_>>()
         );
     }
+
+    #[test]
+    fn preprocess_exec_batch() {
+        let start = ["-exec", "cmd", "{}", "+"];
+        assert_eq!(
+            preprocess_args(start.iter().map(|s| s.to_string())).unwrap(),
+            vec!["--exec", "+\u{0}cmd\u{0}{}"]
+                .iter()
+                .map(|s| s.to_string())
+                .collect::<Vec<_>>()
+        );
+    }
 }
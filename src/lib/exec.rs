@@ -0,0 +1,223 @@
+//! Shell-free `-exec` support: direct argv spawning (no `sh -c`), `{} +`
+//! batching, and a `read2`-style concurrent capture of a child's stdout and
+//! stderr so one full pipe can't block the other.
+
+use std::io::{self, Read};
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::Mutex;
+
+/// `preprocess_args` encodes one `-exec ... ;`/`-exec ... +` invocation as a
+/// single string: a leading terminator marker (`;` or `+`), then the
+/// command's own argv tokens, all NUL-joined so that an argument containing
+/// spaces can't be mistaken for a token boundary. A value passed straight
+/// to `--exec` (with no marker) is one command split on whitespace, run as
+/// with a `;` terminator.
+pub(crate) struct ExecSpec {
+    pub(crate) argv: Vec<String>,
+    pub(crate) batch: bool,
+}
+
+impl ExecSpec {
+    /// Decode the string produced by `preprocess_args`.
+    pub(crate) fn parse(raw: &str) -> ExecSpec {
+        let mut fields = raw.split('\u{0}');
+        match fields.next() {
+            Some(marker @ (";" | "+")) => ExecSpec {
+                argv: fields.map(String::from).collect(),
+                batch: marker == "+",
+            },
+            _ => ExecSpec {
+                argv: raw.split_whitespace().map(String::from).collect(),
+                batch: false,
+            },
+        }
+    }
+}
+
+/// Run `argv` (`argv[0]` is the program, the rest its arguments) directly,
+/// with no shell in between, returning whether it exited successfully.
+/// stdout/stderr are captured concurrently and written back out once the
+/// child has finished, so output from several concurrent `-exec`s (as
+/// happens under [`crate::lib::crawl_path_parallel`]) can't interleave
+/// mid-line.
+pub(crate) fn run<I>(mut argv: I) -> io::Result<bool>
+where
+    I: Iterator<Item = String>,
+{
+    let program = match argv.next() {
+        Some(program) => program,
+        None => return Ok(false),
+    };
+    let mut child = Command::new(program)
+        .args(argv)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let (out, err) = read2(&mut child)?;
+    let status = child.wait()?;
+    io::Write::write_all(&mut io::stdout().lock(), &out)?;
+    io::Write::write_all(&mut io::stderr().lock(), &err)?;
+    Ok(status.success())
+}
+
+/// Read a child's stdout and stderr concurrently via `poll(2)`, the way
+/// cargo-util's `read2` does, instead of reading one to EOF before starting
+/// on the other: a child that fills one pipe's kernel buffer while we're
+/// blocked reading from the other would otherwise deadlock.
+fn read2(child: &mut Child) -> io::Result<(Vec<u8>, Vec<u8>)> {
+    let mut out_pipe = child.stdout.take().expect("stdout was piped");
+    let mut err_pipe = child.stderr.take().expect("stderr was piped");
+    set_nonblocking(out_pipe.as_raw_fd())?;
+    set_nonblocking(err_pipe.as_raw_fd())?;
+
+    let mut out = Vec::new();
+    let mut err = Vec::new();
+    let mut out_open = true;
+    let mut err_open = true;
+    let mut buf = [0u8; 8192];
+
+    while out_open || err_open {
+        let mut fds = Vec::with_capacity(2);
+        if out_open {
+            fds.push(libc::pollfd {
+                fd: out_pipe.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            });
+        }
+        if err_open {
+            fds.push(libc::pollfd {
+                fd: err_pipe.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            });
+        }
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut polled = fds.into_iter();
+        if out_open {
+            if polled.next().unwrap().revents != 0 {
+                drain(&mut out_pipe, &mut buf, &mut out, &mut out_open)?;
+            }
+        }
+        if err_open && polled.next().unwrap().revents != 0 {
+            drain(&mut err_pipe, &mut buf, &mut err, &mut err_open)?;
+        }
+    }
+    Ok((out, err))
+}
+
+/// Read whatever is currently available from `pipe` into `into`, marking
+/// `open` false once the child has closed its end.
+fn drain(
+    pipe: &mut impl Read,
+    buf: &mut [u8],
+    into: &mut Vec<u8>,
+    open: &mut bool,
+) -> io::Result<()> {
+    match pipe.read(buf) {
+        Ok(0) => *open = false,
+        Ok(n) => into.extend_from_slice(&buf[..n]),
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+        Err(e) => return Err(e),
+    }
+    Ok(())
+}
+
+fn set_nonblocking(fd: std::os::unix::io::RawFd) -> io::Result<()> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL, 0) };
+    if flags < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// A conservative ceiling for one command invocation's total argument size,
+/// leaving headroom under the system's real `ARG_MAX` for the command's own
+/// fixed arguments and the inherited environment (both of which also count
+/// against the kernel's limit).
+fn arg_max() -> usize {
+    const FALLBACK: usize = 128 * 1024;
+    let limit = unsafe { libc::sysconf(libc::_SC_ARG_MAX) };
+    if limit > 0 {
+        limit as usize / 2
+    } else {
+        FALLBACK
+    }
+}
+
+/// A deferred `-exec command ... +` invocation. Matched paths accumulate in
+/// `pending` instead of spawning a process immediately; once accumulating
+/// one more would push the batch over [`arg_max`], the command runs over
+/// whatever has accumulated so far. Whatever is left over once the crawl
+/// finishes is run by an explicit [`ExecBatch::flush`].
+pub struct ExecBatch {
+    head: Vec<String>,
+    tail: Vec<String>,
+    pending: Mutex<Vec<PathBuf>>,
+}
+
+impl ExecBatch {
+    /// Build a batch from a command's argv, where exactly one token may be
+    /// the literal `{}` placeholder marking where matched paths are
+    /// inserted (GNU find's `+` terminator, unlike `;`, requires `{}` to be
+    /// its own trailing argument rather than embeddable in a larger one).
+    pub(crate) fn new(argv: Vec<String>) -> Self {
+        let mut argv = argv;
+        let (head, tail) = match argv.iter().position(|a| a == "{}") {
+            Some(i) => {
+                let tail = argv.split_off(i + 1);
+                argv.pop();
+                (argv, tail)
+            }
+            None => (argv, Vec::new()),
+        };
+        ExecBatch {
+            head,
+            tail,
+            pending: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Queue `path`, flushing the batch accumulated so far first if adding
+    /// it would exceed [`arg_max`].
+    pub(crate) fn push(&self, path: PathBuf) -> io::Result<()> {
+        let mut pending = self.pending.lock().unwrap();
+        let added = path.as_os_str().len() + 1;
+        let accumulated: usize = pending.iter().map(|p| p.as_os_str().len() + 1).sum();
+        if !pending.is_empty() && accumulated + added > arg_max() {
+            let chunk = std::mem::take(&mut *pending);
+            drop(pending);
+            self.run_chunk(&chunk)?;
+            pending = self.pending.lock().unwrap();
+        }
+        pending.push(path);
+        Ok(())
+    }
+
+    /// Run the command once more over whatever is left in the batch. Called
+    /// once the crawl that fed this batch has finished.
+    pub fn flush(&self) -> io::Result<bool> {
+        let chunk = std::mem::take(&mut *self.pending.lock().unwrap());
+        if chunk.is_empty() {
+            return Ok(true);
+        }
+        self.run_chunk(&chunk)
+    }
+
+    fn run_chunk(&self, chunk: &[PathBuf]) -> io::Result<bool> {
+        let args = self
+            .head
+            .iter()
+            .cloned()
+            .chain(chunk.iter().map(|p| p.to_string_lossy().into_owned()))
+            .chain(self.tail.iter().cloned());
+        run(args)
+    }
+}
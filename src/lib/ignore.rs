@@ -0,0 +1,309 @@
+//! `.gitignore`-style ignore file support for [`crate::lib::crawl_path`] and
+//! [`crate::lib::crawl_path_parallel`].
+//!
+//! Each directory entered during the crawl may hold a `.gitignore`,
+//! `.ignore`, or `.fdignore` file. The rules it contains apply to that
+//! directory and everything below it, with rules from a directory nearer to
+//! the candidate path taking precedence over ones from further away (the
+//! same "nearest file wins" semantics as `git` and `fd`).
+
+use regex::Regex;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// A single compiled pattern from an ignore file.
+pub(crate) struct Rule {
+    regex: Regex,
+    negate: bool,
+    dir_only: bool,
+}
+
+/// Parse every non-comment, non-blank line of an ignore file's contents into
+/// [`Rule`]s. Lines that fail to compile (which should not happen for valid
+/// gitignore syntax) are silently dropped rather than aborting the crawl.
+pub(crate) fn parse_ignore_file(contents: &str) -> Vec<Rule> {
+    contents.lines().filter_map(parse_pattern).collect()
+}
+
+/// Parse one line of an ignore file into a [`Rule`], or `None` if the line is
+/// blank, a comment, or compiles to nothing (e.g. a bare `!`).
+fn parse_pattern(line: &str) -> Option<Rule> {
+    let line = line.trim_end();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let (negate, line) = match line.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    let (dir_only, line) = match line.strip_suffix('/') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    if line.is_empty() {
+        return None;
+    }
+    // A slash anywhere but the very end anchors the pattern to the directory
+    // holding the ignore file; a pattern with no interior slash may match at
+    // any depth below it.
+    let anchored = line.starts_with('/') || line[1..].contains('/');
+    let pattern = line.strip_prefix('/').unwrap_or(line);
+    let regex = pattern_to_regex(pattern, anchored).ok()?;
+    Some(Rule {
+        regex,
+        negate,
+        dir_only,
+    })
+}
+
+/// Translate one gitignore glob pattern into a regular expression, anchored
+/// to match the whole (`/`-joined) relative path. `**` is only recognised as
+/// a whole path segment, matching zero or more intermediate directories, as
+/// in `git`'s own pattern format: a leading `**/` matches at any depth, a
+/// trailing `/**` matches everything below that point, and a `**` in the
+/// middle matches zero or more whole directories.
+fn pattern_to_regex(pattern: &str, anchored: bool) -> Result<Regex, regex::Error> {
+    if pattern == "**" {
+        return Regex::new("^.*$");
+    }
+    let mut out = String::from("^");
+    if !anchored {
+        out.push_str("(?:.*/)?");
+    }
+    let segments: Vec<&str> = pattern.split('/').collect();
+    let last = segments.len() - 1;
+    let mut need_sep = false;
+    for (i, seg) in segments.iter().enumerate() {
+        if *seg == "**" {
+            if i == last {
+                out.push_str("/.*");
+            } else if i == 0 {
+                out.push_str("(?:.*/)?");
+            } else {
+                out.push_str("/(?:.*/)?");
+            }
+            need_sep = false;
+        } else {
+            if need_sep {
+                out.push('/');
+            }
+            out.push_str(&translate_segment(seg));
+            need_sep = true;
+        }
+    }
+    out.push('$');
+    Regex::new(&out)
+}
+
+/// Translate a single path segment (no `/`) of a glob pattern into a regex
+/// fragment: `*` and `?` behave as usual but never cross a `/`, and bracket
+/// expressions (including gitignore's `[!...]` negation) are passed through.
+fn translate_segment(seg: &str) -> String {
+    let mut out = String::new();
+    let mut chars = seg.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => out.push_str("[^/]*"),
+            '?' => out.push_str("[^/]"),
+            '[' => {
+                out.push('[');
+                if chars.peek() == Some(&'!') {
+                    out.push('^');
+                    chars.next();
+                }
+                for c in chars.by_ref() {
+                    out.push(c);
+                    if c == ']' {
+                        break;
+                    }
+                }
+            }
+            c if "\\.+(){}|^$".contains(c) => {
+                out.push('\\');
+                out.push(c);
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Read and parse whichever of `.gitignore`, `.ignore`, and `.fdignore` exist
+/// directly inside `dir`, in that order, so that later files' rules take
+/// precedence over earlier ones when combined by [`apply_layer`].
+pub(crate) fn load_layer(dir: &Path) -> Vec<Rule> {
+    let mut rules = Vec::new();
+    for name in [".gitignore", ".ignore", ".fdignore"] {
+        if let Ok(contents) = std::fs::read_to_string(dir.join(name)) {
+            rules.extend(parse_ignore_file(&contents));
+        }
+    }
+    rules
+}
+
+/// Fold `rules` (belonging to the directory `rel` is relative to) into
+/// `ignored`: the last matching rule wins, and a rule with `dir_only` set
+/// only applies to directories.
+fn apply_layer(rules: &[Rule], rel: &str, is_dir: bool, ignored: &mut bool) {
+    for rule in rules {
+        if rule.dir_only && !is_dir {
+            continue;
+        }
+        if rule.regex.is_match(rel) {
+            *ignored = !rule.negate;
+        }
+    }
+}
+
+/// A stack of ignore-file layers, one per directory on the current path from
+/// a starting point down to wherever [`crawl_path`](crate::lib::crawl_path)'s
+/// recursion currently is. Grown with [`IgnoreStack::enter`] on the way down
+/// and shrunk with [`IgnoreStack::leave`] on the way back up, mirroring the
+/// recursion itself.
+pub struct IgnoreStack {
+    enabled: bool,
+    layers: Vec<(PathBuf, Vec<Rule>)>,
+}
+
+impl IgnoreStack {
+    /// Create a stack. When `enabled` is `false` (the `--no-ignore` case)
+    /// every method becomes a no-op and [`IgnoreStack::is_ignored`] always
+    /// returns `false`.
+    pub fn new(enabled: bool) -> Self {
+        IgnoreStack {
+            enabled,
+            layers: Vec::new(),
+        }
+    }
+
+    /// Enter `dir`, pushing a layer built from any ignore file found
+    /// directly inside it (an empty layer if there is none, so `enter` and
+    /// `leave` stay balanced one-to-one with recursion depth).
+    pub fn enter(&mut self, dir: &Path) {
+        if !self.enabled {
+            return;
+        }
+        self.layers.push((dir.to_path_buf(), load_layer(dir)));
+    }
+
+    /// Leave the directory most recently entered.
+    pub fn leave(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        self.layers.pop();
+    }
+
+    /// Whether `path`, a descendant of every directory currently on the
+    /// stack, should be skipped. `is_dir` selects whether `/`-suffixed,
+    /// directory-only rules apply.
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        let mut ignored = false;
+        for (dir, rules) in &self.layers {
+            if let Ok(rel) = path.strip_prefix(dir) {
+                let rel = rel.to_string_lossy().replace('\\', "/");
+                apply_layer(rules, &rel, is_dir, &mut ignored);
+            }
+        }
+        ignored
+    }
+}
+
+// Directory -> its own (non-cumulative) rule layer, read at most once.
+type LayerCache = Mutex<HashMap<PathBuf, Arc<Vec<Rule>>>>;
+
+/// A thread-safe equivalent of [`IgnoreStack`] for
+/// [`crawl_path_parallel`](crate::lib::crawl_path_parallel), where worker
+/// threads descend independent, interleaved subtrees and so cannot share a
+/// single linear stack. Instead, each directory's layer is parsed at most
+/// once and cached, and the applicable layers for a path are recomputed
+/// on demand by walking from `root` down to it.
+pub struct IgnoreCache {
+    enabled: bool,
+    root: PathBuf,
+    layers: LayerCache,
+}
+
+impl IgnoreCache {
+    /// Create a cache rooted at `root`, the starting point of the crawl.
+    pub fn new(root: &Path, enabled: bool) -> Self {
+        IgnoreCache {
+            enabled,
+            root: root.to_path_buf(),
+            layers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether `path` (somewhere under `root`) should be skipped.
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        let mut dirs = vec![self.root.clone()];
+        if let Ok(rel) = path.strip_prefix(&self.root) {
+            let comps: Vec<_> = rel.components().collect();
+            let mut acc = self.root.clone();
+            for comp in &comps[..comps.len().saturating_sub(1)] {
+                acc.push(comp);
+                dirs.push(acc.clone());
+            }
+        }
+        let mut ignored = false;
+        for dir in &dirs {
+            let rules = {
+                let mut layers = self.layers.lock().unwrap();
+                layers
+                    .entry(dir.clone())
+                    .or_insert_with(|| Arc::new(load_layer(dir)))
+                    .clone()
+            };
+            if let Ok(rel) = path.strip_prefix(dir) {
+                // `rel` is empty exactly when `dir == path`, i.e. `path` is
+                // the root being tested against its own layer. `IgnoreStack`
+                // never does this (a directory only filters its children),
+                // so skip it here too to keep the two traversals agreeing.
+                if rel.as_os_str().is_empty() {
+                    continue;
+                }
+                let rel = rel.to_string_lossy().replace('\\', "/");
+                apply_layer(&rules, &rel, is_dir, &mut ignored);
+            }
+        }
+        ignored
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn double_star_leading() {
+        let re = pattern_to_regex("**/foo", true).unwrap();
+        assert!(re.is_match("foo"));
+        assert!(re.is_match("a/foo"));
+        assert!(re.is_match("a/b/foo"));
+        assert!(!re.is_match("foobar"));
+    }
+
+    #[test]
+    fn double_star_middle() {
+        let re = pattern_to_regex("a/**/b", true).unwrap();
+        assert!(re.is_match("a/b"));
+        assert!(re.is_match("a/x/b"));
+        assert!(re.is_match("a/x/y/b"));
+        assert!(!re.is_match("a/b/c"));
+    }
+
+    #[test]
+    fn double_star_trailing() {
+        let re = pattern_to_regex("a/**", true).unwrap();
+        assert!(re.is_match("a/b"));
+        assert!(re.is_match("a/b/c"));
+        assert!(!re.is_match("a"));
+    }
+}
@@ -0,0 +1,139 @@
+//! `--watch`/`-w` continuous mode: after the initial crawl, keep running and
+//! re-emit results whenever a starting point's tree changes, the way
+//! `watchexec` re-runs a command on file changes.
+
+use super::exec;
+use super::{crawl_path, Error, ExecBatch, IgnoreStack, Predicate};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::time::Duration;
+
+/// Bursts of filesystem events (e.g. the several writes one editor save
+/// produces) arriving within this window of each other are coalesced into a
+/// single re-crawl, instead of thrashing once per individual event.
+const DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// Run the initial crawl, then watch every starting point and re-run it (or,
+/// if `on_change` is given, run that command instead of reprinting) each
+/// time a settled batch of filesystem events arrives. Runs until the
+/// watcher itself fails or is disconnected.
+#[allow(clippy::too_many_arguments)]
+pub fn watch(
+    starting_points: &[std::path::PathBuf],
+    predicate: &Predicate,
+    follow_syms: bool,
+    respect_ignore: bool,
+    mindepth: usize,
+    maxdepth: Option<usize>,
+    on_change: Option<&[String]>,
+    exec_batches: &[std::sync::Arc<ExecBatch>],
+) -> Result<(), Error<String>> {
+    let (tx, rx) = channel::<notify::Result<notify::Event>>();
+    let mut watcher: RecommendedWatcher =
+        notify::recommended_watcher(tx).map_err(|e| Error::Custom(e.to_string()))?;
+    for start in starting_points {
+        watcher
+            .watch(start, RecursiveMode::Recursive)
+            .map_err(|e| Error::Custom(e.to_string()))?;
+    }
+
+    run_once(
+        starting_points,
+        predicate,
+        follow_syms,
+        respect_ignore,
+        mindepth,
+        maxdepth,
+        None,
+        exec_batches,
+    );
+
+    loop {
+        match rx.recv() {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => return Err(Error::Custom(e.to_string())),
+            // The watcher (and its sender) was dropped; nothing more to wait for.
+            Err(_) => return Ok(()),
+        }
+        // Drain whatever else settles within the debounce window so one
+        // burst of events triggers exactly one re-crawl.
+        loop {
+            match rx.recv_timeout(DEBOUNCE) {
+                Ok(Ok(_)) => continue,
+                Ok(Err(e)) => return Err(Error::Custom(e.to_string())),
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+        }
+        run_once(
+            starting_points,
+            predicate,
+            follow_syms,
+            respect_ignore,
+            mindepth,
+            maxdepth,
+            on_change,
+            exec_batches,
+        );
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run_once(
+    starting_points: &[std::path::PathBuf],
+    predicate: &Predicate,
+    follow_syms: bool,
+    respect_ignore: bool,
+    mindepth: usize,
+    maxdepth: Option<usize>,
+    on_change: Option<&[String]>,
+    exec_batches: &[std::sync::Arc<ExecBatch>],
+) {
+    if let Some(argv) = on_change {
+        if let Err(e) = exec::run(argv.iter().cloned()) {
+            Error::Custom(&e).sig();
+        }
+        return;
+    }
+    for start in starting_points {
+        crawl_one(
+            start,
+            predicate,
+            follow_syms,
+            respect_ignore,
+            mindepth,
+            maxdepth,
+        );
+    }
+    for batch in exec_batches {
+        if let Err(e) = batch.flush() {
+            Error::Custom(&e).sig();
+        }
+    }
+}
+
+fn crawl_one(
+    start: &Path,
+    predicate: &Predicate,
+    follow_syms: bool,
+    respect_ignore: bool,
+    mindepth: usize,
+    maxdepth: Option<usize>,
+) {
+    let mut visited = HashSet::new();
+    let mut ignore = IgnoreStack::new(respect_ignore);
+    if let Err(e) = crawl_path(
+        start,
+        predicate,
+        follow_syms,
+        &mut visited,
+        &mut ignore,
+        0,
+        mindepth,
+        maxdepth,
+    ) {
+        Error::from_io(e, start.display()).sig();
+    }
+}
@@ -5,7 +5,7 @@ use std::path::Path;
 use std::process::exit;
 
 mod lib;
-use lib::{crawl_path, form_predicate, Error};
+use lib::{crawl_path, crawl_path_parallel, form_predicate, watch, Error, IgnoreStack, SizeFilter};
 
 #[cfg(test)]
 mod test {
@@ -31,7 +31,7 @@ mod test {
                 "--name",
                 "thing*",
                 "--exec",
-                "cmd -type",
+                ";\u{0}cmd\u{0}-type",
                 "--type",
                 "b"
             ]
@@ -40,6 +40,18 @@ mod test {
             .collect::<Vec<_>>()
         );
     }
+
+    #[test]
+    fn preprocess_exec_batch() {
+        let start = ["-exec", "cmd", "{}", "+"];
+        assert_eq!(
+            preprocess_args(start.iter().map(|s| s.to_string())).unwrap(),
+            vec!["--exec", "+\u{0}cmd\u{0}{}"]
+                .iter()
+                .map(|s| s.to_string())
+                .collect::<Vec<_>>()
+        );
+    }
 }
 
 /// Process command line arguments into a usefull output.
@@ -51,6 +63,26 @@ fn getopts(preprocessed_args: Vec<String>) -> ArgMatches {
         .about("An reimplimentation of find for scholastic purposes.")
         .arg(Arg::new("L").short('L').about("Follow symbolic links"))
         .arg(Arg::new("C").short('C').about("Canonicalize paths"))
+        .arg(Arg::new("no-ignore").short('I').long("no-ignore").about(
+            "Don't respect .gitignore, .ignore, and .fdignore files \
+                     encountered while crawling",
+        ))
+        .arg(
+            Arg::new("threads")
+                .short('j')
+                .long("threads")
+                .value_name("N")
+                .about(
+                    "Crawl using N worker threads sharing a work queue, instead \
+                     of a single-threaded recursive descent",
+                )
+                .takes_value(true)
+                .validator(|s| match s.parse::<usize>() {
+                    Ok(n) if n > 0 => Ok(()),
+                    Ok(_) => Err(String::from("number of threads must be greater than 0")),
+                    Err(e) => Err(e.to_string()),
+                }),
+        )
         .arg(
             Arg::new("starting_point")
                 .about("Starting point for search")
@@ -64,6 +96,24 @@ fn getopts(preprocessed_args: Vec<String>) -> ArgMatches {
                 .takes_value(true)
                 .multiple_occurrences(true),
         )
+        .arg(
+            Arg::new("regex")
+                .short('r')
+                .long("regex")
+                .value_name("pattern")
+                .about("Filters file names according to a regular expression.")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .validator(|s| match regex::Regex::new(s) {
+                    Ok(_) => Ok(()),
+                    Err(e) => Err(e.to_string()),
+                }),
+        )
+        .arg(
+            Arg::new("full-path").long("full-path").about(
+                "Match `--regex` patterns against the full path instead of just the file name.",
+            ),
+        )
         .arg(
             Arg::new("mtime")
                 .long("mtime")
@@ -79,6 +129,38 @@ fn getopts(preprocessed_args: Vec<String>) -> ArgMatches {
                 .allow_hyphen_values(true)
                 .multiple_occurrences(true),
         )
+        .arg(
+            Arg::new("size")
+                .long("size")
+                .about(
+                    "Filters by file size, compared with N (in 512-byte blocks \
+                     unless a `c`/`k`/`M`/`G` suffix is given): greater (+N), \
+                     less (-N), or exactly (N) the given size",
+                )
+                .takes_value(true)
+                .value_name("[+-]N[bckMG]")
+                .validator(|s| SizeFilter::parse(s).map(|_| ()))
+                .allow_hyphen_values(true)
+                .multiple_occurrences(true),
+        )
+        .arg(
+            Arg::new("mindepth")
+                .long("mindepth")
+                .about(
+                    "Don't report matches at a depth less than `n` levels below a starting point",
+                )
+                .takes_value(true)
+                .value_name("n")
+                .validator(|s| s.parse::<usize>().map(|_| ()).map_err(|e| e.to_string())),
+        )
+        .arg(
+            Arg::new("maxdepth")
+                .long("maxdepth")
+                .about("Descend at most `n` levels below a starting point")
+                .takes_value(true)
+                .value_name("n")
+                .validator(|s| s.parse::<usize>().map(|_| ()).map_err(|e| e.to_string())),
+        )
         .arg(
             Arg::new("type")
                 .long("type")
@@ -94,10 +176,13 @@ fn getopts(preprocessed_args: Vec<String>) -> ArgMatches {
                 .about(
                     "Execute `command`; true if 0 status is returned. All \
                                  following arguments to find are taken to be arguments \
-                                 to the command until an argument consisting of `;' is \
-                                 encountered. The string `{}' is replaced by the current \
-                                 file name being processed everywhere it occurs in the \
-                                 arguments to the command.",
+                                 to the command until an argument consisting of `;' or \
+                                 `+' is encountered. The string `{}' is replaced by the \
+                                 current file name being processed everywhere it occurs \
+                                 in the arguments to the command. If the terminator is \
+                                 `+' instead, `{}' must be the final argument and the \
+                                 command is invoked with as many matched paths at once \
+                                 as will fit, instead of once per match.",
                 )
                 .takes_value(true)
                 .value_name("command")
@@ -111,6 +196,21 @@ fn getopts(preprocessed_args: Vec<String>) -> ArgMatches {
                 )
                 .multiple_occurrences(true),
         )
+        .arg(Arg::new("watch").short('w').long("watch").about(
+            "After the initial search, keep running and re-run it whenever a starting \
+             point's files change, debouncing bursts of changes a short moment apart",
+        ))
+        .arg(
+            Arg::new("on-change")
+                .long("on-change")
+                .about(
+                    "With --watch, run `command` (split on whitespace, no shell involved) \
+                     after each change instead of re-printing the matches",
+                )
+                .takes_value(true)
+                .value_name("command")
+                .requires("watch"),
+        )
         .get_matches_from(preprocessed_args)
 }
 
@@ -131,8 +231,16 @@ where
     for arg in args.into_iter() {
         let arg = arg.into();
         if let Some(mut cmd) = exec {
-            if &arg == ";" {
-                out.push(cmd.join(" "));
+            if &arg == ";" || &arg == "+" {
+                // Encode the `;`/`+` terminator as a leading NUL-joined
+                // field so the exec predicate can tell the two apart
+                // without re-parsing shell syntax, then preserve the
+                // command's argv tokens verbatim (no shell joining) so `{}`
+                // substitution stays a plain argv substitution later on.
+                let marker = if &arg == "+" { "+" } else { ";" };
+                let mut encoded = vec![marker.to_string()];
+                encoded.extend(cmd);
+                out.push(encoded.join("\u{0}"));
                 exec = None;
             } else {
                 exec = Some({
@@ -147,6 +255,7 @@ where
                 "-name" => out.push(String::from("--name")),
                 "-type" => out.push(String::from("--type")),
                 "-mtime" => out.push(String::from("--mtime")),
+                "-size" => out.push(String::from("--size")),
                 "-exec" => {
                     out.push(String::from("--exec"));
                     exec = Some(Vec::new());
@@ -185,16 +294,64 @@ fn main() -> io::Result<()> {
             })
             .collect()
     };
-    let mut error_no: i32 = 0;
-    for starting_point in starting_points {
-        let mut visited = HashSet::new();
-        let predicate = form_predicate(&opts);
-        match crawl_path(
-            &starting_point,
+    let threads: Option<usize> = opts.value_of("threads").map(|n| n.parse().unwrap());
+    let respect_ignore = !opts.is_present("no-ignore");
+    let mindepth: usize = opts
+        .value_of("mindepth")
+        .map(|n| n.parse().unwrap())
+        .unwrap_or(0);
+    let maxdepth: Option<usize> = opts.value_of("maxdepth").map(|n| n.parse().unwrap());
+
+    if opts.is_present("watch") {
+        let (predicate, exec_batches) = form_predicate(&opts);
+        let on_change: Option<Vec<String>> = opts
+            .value_of("on-change")
+            .map(|cmd| cmd.split_whitespace().map(String::from).collect());
+        if let Err(e) = watch(
+            &starting_points,
             &predicate,
             opts.is_present("L"),
-            &mut visited,
+            respect_ignore,
+            mindepth,
+            maxdepth,
+            on_change.as_deref(),
+            &exec_batches,
         ) {
+            e.sig();
+            exit(1);
+        }
+        exit(0);
+    }
+
+    let mut error_no: i32 = 0;
+    for starting_point in starting_points {
+        let (predicate, exec_batches) = form_predicate(&opts);
+        let result = match threads {
+            Some(threads) => crawl_path_parallel(
+                &starting_point,
+                &predicate,
+                opts.is_present("L"),
+                threads,
+                respect_ignore,
+                mindepth,
+                maxdepth,
+            ),
+            None => {
+                let mut visited = HashSet::new();
+                let mut ignore = IgnoreStack::new(respect_ignore);
+                crawl_path(
+                    &starting_point,
+                    &predicate,
+                    opts.is_present("L"),
+                    &mut visited,
+                    &mut ignore,
+                    0,
+                    mindepth,
+                    maxdepth,
+                )
+            }
+        };
+        match result {
             Ok(sig_error) => {
                 if sig_error {
                     error_no = 1;
@@ -205,6 +362,12 @@ fn main() -> io::Result<()> {
                 error_no = 1;
             }
         }
+        for batch in &exec_batches {
+            if let Err(error) = batch.flush() {
+                Error::from_io(error, starting_point.display()).sig();
+                error_no = 1;
+            }
+        }
     }
     exit(error_no);
 }